@@ -2,11 +2,16 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use image::ImageReader; // Fixed: Use direct image::ImageReader (no io::Reader alias)
 use oxipng::{optimize_from_memory, Options};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use rayon::prelude::*;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Zap metadata from PNG/JPG images in a directory", long_about = None)]
@@ -34,24 +39,218 @@ struct Args {
     /// Backup originals with .bak suffix (for in-place runs)
     #[arg(short = 'b', long, default_value_t = false)]
     backup: bool,
+
+    /// Fully re-encode pixels instead of stripping metadata segments byte-for-byte.
+    /// The lossless strip path only covers JPEG and PNG; all other formats always
+    /// re-encode. Without this flag animated GIF/WebP are skipped to avoid losing
+    /// frames; with it they are flattened to their first frame.
+    #[arg(long, default_value_t = false)]
+    reencode: bool,
+
+    /// Only process these extensions (comma-separated), overriding the default set
+    #[arg(long, value_delimiter = ',')]
+    include: Vec<String>,
+
+    /// Skip these extensions (comma-separated), subtracted from the set being processed
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// Skip files unchanged (same mtime and size) since the last run, tracked in a manifest
+    #[arg(long, default_value_t = false)]
+    incremental: bool,
+
+    /// Write cleaned images into a single compressed tar archive instead of a directory
+    #[arg(long)]
+    archive: Option<PathBuf>,
+
+    /// Inverse of --archive: extract an archive back out into --output (or --input)
+    #[arg(long, default_value_t = false)]
+    extract: bool,
+
+    /// Compression for --archive: zstd, gzip, or xz (default: inferred from the archive extension)
+    #[arg(long)]
+    compression: Option<Compression>,
+
+    /// Compression window size / level: zstd window-log, gzip/xz level (default: format default)
+    #[arg(long)]
+    window: Option<u32>,
+
+    /// Clear extended attributes (Finder tags, com.apple.metadata:*, user.xdg.*) on the output
+    #[arg(long, default_value_t = false)]
+    xattrs: bool,
+
+    /// Handle adjacent sidecar metadata files (.xmp, .aae, .json) sharing the image's stem:
+    /// removed for in-place runs, copied unchanged into --output, or added to --archive
+    #[arg(long, default_value_t = false)]
+    sidecars: bool,
+
+    /// For RAW files, demosaic and export a metadata-free image instead of stripping in place
+    #[arg(long, default_value_t = false)]
+    develop: bool,
+}
+
+/// Sidecar file extensions that carry image metadata alongside the original.
+const SIDECAR_EXTS: &[&str] = &["xmp", "aae", "json"];
+
+/// Compression codec for archive output.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Compression {
+    Zstd,
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    /// Infer the codec from an archive path's extension, defaulting to zstd.
+    fn infer(path: &Path) -> Self {
+        match path.extension().and_then(|s| s.to_str()).map(str::to_lowercase).as_deref() {
+            Some("gz") | Some("gzip") => Compression::Gzip,
+            Some("xz") => Compression::Xz,
+            _ => Compression::Zstd,
+        }
+    }
+}
+
+/// Name of the manifest recording which files have already been cleaned.
+const STATE_FILE: &str = ".metazap-state.json";
+
+/// Per-file fingerprint used to detect changes between incremental runs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct FileState {
+    /// Modification time in whole seconds since the Unix epoch.
+    modified: u64,
+    /// File size in bytes.
+    len: u64,
+}
+
+/// On-disk manifest keyed by absolute path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    files: HashMap<String, FileState>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Compute a file's `(modified, len)` fingerprint the way a backup tool would.
+fn file_state(path: &Path) -> Option<FileState> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(FileState { modified, len: meta.len() })
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    // Inverse mode: unpack a previously-written archive and exit.
+    if args.extract {
+        let archive = args
+            .archive
+            .as_ref()
+            .context("--extract requires --archive <path>")?;
+        let dest = args.output.as_ref().unwrap_or(&args.input);
+        // Honour an explicit --compression over the filename guess, so an
+        // archive whose name disagrees with how it was written still decodes.
+        let codec = args.compression.unwrap_or_else(|| Compression::infer(archive));
+        let count = extract_archive(archive, dest, codec)?;
+        println!("\nSummary: {} extracted", count);
+        return Ok(());
+    }
+
     if !args.input.exists() {
         anyhow::bail!("Input directory '{}' does not exist", args.input.display());
     }
 
+    // Each archive run starts a fresh tarball, so --incremental (which skips
+    // unchanged sources) would silently omit every previously-cleaned file from
+    // the new archive. The two modes are mutually exclusive.
+    if args.incremental && args.archive.is_some() {
+        anyhow::bail!("--incremental cannot be combined with --archive (a fresh archive would omit skipped files)");
+    }
+
     let output_dir = args.output.as_ref().unwrap_or(&args.input);
     if !output_dir.exists() && !args.dry_run {
         fs::create_dir_all(output_dir).context("Failed to create output directory")?;
     }
 
-    let extensions: Vec<&str> = vec!["png", "jpg", "jpeg"];
+    // Start from the `--include` override if given, otherwise the built-in set of
+    // formats the `image` crate can decode/encode, then subtract `--exclude`.
+    let mut extensions: Vec<String> = if !args.include.is_empty() {
+        args.include.iter().map(|e| e.to_lowercase()).collect()
+    } else {
+        let mut defaults: Vec<String> = ["png", "jpg", "jpeg", "webp", "avif", "tiff", "tif", "bmp", "gif"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if cfg!(feature = "video") {
+            defaults.extend(["mp4", "mov", "mkv", "webm"].iter().map(|s| s.to_string()));
+        }
+        if cfg!(feature = "raw") {
+            // TIFF-derived RAWs can be stripped in place; Fujifilm RAF is a
+            // non-TIFF container the strip path can't parse, so it's only
+            // included when --develop will demosaic it instead.
+            defaults.extend(["cr2", "nef", "arw", "dng"].iter().map(|s| s.to_string()));
+            if args.develop {
+                defaults.push("raf".to_string());
+            }
+        }
+        defaults
+    };
+    let excluded: Vec<String> = args.exclude.iter().map(|e| e.to_lowercase()).collect();
+    extensions.retain(|e| !excluded.contains(e));
+
+    // Archive entries go through the in-memory image path (`zap_bytes`), which
+    // cannot handle video/RAW — those need ffmpeg/imagepipe to write a file.
+    // Drop them from the set when archiving rather than erroring per file.
+    if args.archive.is_some() {
+        const NON_ARCHIVABLE: &[&str] =
+            &["mp4", "mov", "mkv", "webm", "cr2", "nef", "arw", "dng", "raf"];
+        let before = extensions.len();
+        extensions.retain(|e| !NON_ARCHIVABLE.contains(&e.as_str()));
+        if extensions.len() != before {
+            eprintln!("Note: video/RAW formats are not archived; skipping them in --archive mode");
+        }
+    }
+
     let processed = AtomicUsize::new(0);
     let skipped = AtomicUsize::new(0);
     let errors = AtomicUsize::new(0);
+    let xattrs_cleared = AtomicUsize::new(0);
+    let sidecars_handled = AtomicUsize::new(0);
+
+    // Archive output: a single Mutex-guarded tar writer fed from the rayon workers.
+    let archive_writer = match &args.archive {
+        Some(path) => {
+            let codec = args.compression.unwrap_or_else(|| Compression::infer(path));
+            Some(Mutex::new(archive_builder(path, codec, args.window)?))
+        }
+        None => None,
+    };
+    let input_root = args.input.clone();
+
+    // Load (and later rewrite) the incremental manifest from the output root.
+    let state_path = output_dir.join(STATE_FILE);
+    let manifest = Mutex::new(if args.incremental {
+        Manifest::load(&state_path)
+    } else {
+        Manifest::default()
+    });
 
     let walker = WalkDir::new(&args.input)
         .max_depth(if args.recursive { std::usize::MAX } else { 1 })
@@ -59,9 +258,11 @@ fn main() -> Result<()> {
         .filter_map(|e| e.ok())
         .filter(|e| {
             e.file_type().is_file()
-                && extensions.iter().any(|ext| {
-                    e.path().extension().and_then(|s| s.to_str()) == Some(ext)
-                })
+                && e.path()
+                    .extension()
+                    .and_then(|s| s.to_str())
+                    .map(|s| s.to_lowercase())
+                    .map_or(false, |ext| extensions.contains(&ext))
         });
 
     walker.par_bridge().for_each(|entry| {
@@ -77,6 +278,26 @@ fn main() -> Result<()> {
             src_path.to_path_buf()
         };
 
+        // Incremental: skip files whose fingerprint is unchanged since the last run.
+        let abs_key = fs::canonicalize(src_path)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| src_path.to_string_lossy().into_owned());
+        let current_state = file_state(src_path);
+        if args.incremental {
+            if let Some(state) = &current_state {
+                let unchanged = manifest
+                    .lock()
+                    .unwrap()
+                    .files
+                    .get(&abs_key)
+                    .map_or(false, |prev| prev == state);
+                if unchanged {
+                    skipped.fetch_add(1, Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+
         if is_inplace && args.backup {
             let mut backup_path = src_path.to_path_buf();
             if let Some(e) = backup_path.extension() {
@@ -99,16 +320,68 @@ fn main() -> Result<()> {
             }
         }
 
+        // Sidecar files are handled regardless of the in-file outcome (they carry
+        // their own metadata); this honours --dry-run and --backup internally. The
+        // destination mirrors wherever the image itself is going: deleted from the
+        // source only for true in-place runs, otherwise copied alongside the
+        // cleaned output rather than lost from the input tree.
+        if args.sidecars {
+            let sidecar_dest = if let Some(writer) = &archive_writer {
+                SidecarDest::Archive(writer)
+            } else if is_inplace {
+                SidecarDest::InPlace
+            } else {
+                SidecarDest::Directory(output_dir)
+            };
+            match handle_sidecars(src_path, &input_root, sidecar_dest, args.dry_run, args.backup) {
+                Ok(n) => {
+                    sidecars_handled.fetch_add(n, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    eprintln!("Sidecar error for {}: {}", src_path.display(), e);
+                    errors.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
         if args.dry_run {
             println!("Would process: {} -> {}", src_path.display(), dest_path.display());
             processed.fetch_add(1, Ordering::SeqCst);
             return;
         }
 
-        match process_image(src_path, &dest_path, ext, args.optimize) {
-            Ok(_) => {
-                println!("Zapped: {} -> {}", src_path.display(), dest_path.display());
+        let result = if let Some(writer) = &archive_writer {
+            append_to_archive(writer, src_path, &dest_path, &input_root, ext, args.optimize, args.reencode)
+        } else {
+            process_image(src_path, &dest_path, ext, args.optimize, args.reencode, args.develop)
+        };
+
+        match result {
+            Ok(Outcome::Skipped(reason)) => {
+                eprintln!("Skipping {}: {}", src_path.display(), reason);
+                skipped.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(Outcome::Written(actual_dest)) => {
+                println!("Zapped: {} -> {}", src_path.display(), actual_dest.display());
                 processed.fetch_add(1, Ordering::SeqCst);
+                if args.incremental {
+                    // Record the post-zap fingerprint so the next run skips this file.
+                    if let Some(state) = file_state(src_path).or(current_state) {
+                        manifest.lock().unwrap().files.insert(abs_key, state);
+                    }
+                }
+                // Extended attributes live on the destination file itself.
+                if args.xattrs && archive_writer.is_none() {
+                    match clear_xattrs(&actual_dest) {
+                        Ok(n) => {
+                            xattrs_cleared.fetch_add(n, Ordering::SeqCst);
+                        }
+                        Err(e) => {
+                            eprintln!("xattr error for {}: {}", actual_dest.display(), e);
+                            errors.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
             }
             Err(e) => {
                 eprintln!("Error zapping {}: {}", src_path.display(), e);
@@ -117,12 +390,37 @@ fn main() -> Result<()> {
         }
     });
 
+    if args.incremental && !args.dry_run {
+        manifest
+            .into_inner()
+            .unwrap()
+            .save(&state_path)
+            .context("Failed to write incremental manifest")?;
+    }
+
+    // Finish the tar stream and flush/close the compression codec.
+    if let Some(writer) = archive_writer {
+        writer
+            .into_inner()
+            .unwrap()
+            .into_inner()
+            .context("Failed to finalize archive")?
+            .finish()
+            .context("Failed to finalize archive compression")?;
+    }
+
     println!(
         "\nSummary: {} processed, {} skipped, {} errors",
         processed.load(Ordering::SeqCst),
         skipped.load(Ordering::SeqCst),
         errors.load(Ordering::SeqCst)
     );
+    if args.xattrs {
+        println!("  {} files had extended attributes cleared", xattrs_cleared.load(Ordering::SeqCst));
+    }
+    if args.sidecars {
+        println!("  {} sidecar files handled", sidecars_handled.load(Ordering::SeqCst));
+    }
 
     if errors.load(Ordering::SeqCst) > 0 {
         std::process::exit(1);
@@ -131,16 +429,1072 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn process_image(src: &Path, dest: &Path, ext: &str, optimize: bool) -> Result<()> {
-    let img = ImageReader::open(src)?.decode()?;
-    img.save(dest).with_context(|| format!("Failed to save {}", ext.to_uppercase()))?;
+/// Result of zapping a single file: either a path was written, or the file was
+/// deliberately skipped (with a reason to report).
+enum Outcome {
+    Written(PathBuf),
+    Skipped(String),
+}
+
+/// Zap a single file. The byte-for-byte lossless path only covers JPEG and PNG;
+/// every other format (WebP, AVIF, TIFF, BMP, GIF) is decoded and re-encoded via
+/// the `image` crate, which keeps only the first frame — so unless `reencode` is
+/// set, animated GIF/WebP inputs are skipped rather than silently flattened
+/// (with `reencode` they are flattened like any re-encode). On success returns the
+/// path actually written, which may differ from `dest` (e.g. a developed RAW is
+/// exported with a `.png` extension).
+fn process_image(
+    src: &Path,
+    dest: &Path,
+    ext: &str,
+    optimize: bool,
+    reencode: bool,
+    develop: bool,
+) -> Result<Outcome> {
+    let lower = ext.to_lowercase();
+
+    #[cfg(feature = "video")]
+    if matches!(lower.as_str(), "mp4" | "mov" | "mkv" | "webm") {
+        strip_video(src, dest)?;
+        return Ok(Outcome::Written(dest.to_path_buf()));
+    }
 
-    if optimize && ext.to_lowercase() == "png" {
-        let data = fs::read(dest)?;
+    #[cfg(feature = "raw")]
+    if matches!(lower.as_str(), "cr2" | "nef" | "arw" | "dng" | "raf") {
+        return process_raw(src, dest, &lower, develop);
+    }
+    let _ = develop;
+
+    match zap_bytes(src, &lower, optimize, reencode)? {
+        ZapBytes::Skipped(reason) => Ok(Outcome::Skipped(reason)),
+        ZapBytes::Written(bytes) => {
+            fs::write(dest, &bytes)
+                .with_context(|| format!("Failed to write {}", dest.display()))?;
+            Ok(Outcome::Written(dest.to_path_buf()))
+        }
+    }
+}
+
+/// Whether `src` holds more than one frame or page (animated GIF/WebP/AVIF,
+/// multi-page TIFF). Used to avoid routing these through the first-frame-only
+/// re-encode fallback.
+fn is_multiframe(src: &Path, lower: &str) -> bool {
+    use image::AnimationDecoder;
+    match lower {
+        "gif" => {
+            let Ok(file) = fs::File::open(src) else { return false };
+            image::codecs::gif::GifDecoder::new(std::io::BufReader::new(file))
+                .map(|d| d.into_frames().take(2).count() > 1)
+                .unwrap_or(false)
+        }
+        "webp" => {
+            let Ok(file) = fs::File::open(src) else { return false };
+            image::codecs::webp::WebPDecoder::new(std::io::BufReader::new(file))
+                .map(|d| d.has_animation())
+                .unwrap_or(false)
+        }
+        "tiff" | "tif" => fs::read(src).map_or(false, |data| is_multipage_tiff(&data).unwrap_or(false)),
+        "avif" => fs::read(src).map_or(false, |data| is_animated_avif(&data).unwrap_or(false)),
+        _ => false,
+    }
+}
+
+/// Whether a TIFF holds more than one IFD (page). Walks the IFD chain via each
+/// IFD's "next IFD offset" field rather than decoding pixel data, so it works
+/// regardless of what the page contents are.
+fn is_multipage_tiff(data: &[u8]) -> Result<bool> {
+    if data.len() < 8 {
+        anyhow::bail!("file too short to be TIFF");
+    }
+    let little = match &data[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => anyhow::bail!("not a TIFF (bad byte order mark)"),
+    };
+    let rd16 = |o: usize| -> Option<u16> {
+        let b = data.get(o..o + 2)?;
+        Some(if little { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    };
+    let rd32 = |o: usize| -> Option<u32> {
+        let b = data.get(o..o + 4)?;
+        Some(if little {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    };
+
+    let mut pages = 0;
+    let mut ifd = rd32(4).unwrap_or(0) as usize;
+    // A malformed or cyclic chain must not hang the walk; two pages is all the
+    // caller needs to know.
+    while ifd != 0 && pages < 2 {
+        let count = match rd16(ifd) {
+            Some(c) => c as usize,
+            None => break,
+        };
+        pages += 1;
+        let next_pos = ifd + 2 + count * 12;
+        ifd = rd32(next_pos).unwrap_or(0) as usize;
+    }
+    Ok(pages > 1)
+}
+
+/// Whether an AVIF container is an image sequence (animated) rather than a
+/// single still. AVIF reuses the HEIF/ISOBMFF box structure; an image
+/// sequence advertises the "avis" brand in its `ftyp` box, a still the "avif"
+/// brand.
+fn is_animated_avif(data: &[u8]) -> Result<bool> {
+    if data.len() < 16 {
+        anyhow::bail!("file too short to be AVIF");
+    }
+    let box_size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if &data[4..8] != b"ftyp" || box_size < 16 || box_size > data.len() {
+        anyhow::bail!("not an AVIF (missing ftyp box)");
+    }
+    if &data[8..12] == b"avis" {
+        return Ok(true);
+    }
+    // Compatible brands follow major_brand (4 bytes) + minor_version (4 bytes),
+    // each its own 4-byte tag through the end of the box.
+    Ok(data[16..box_size].chunks_exact(4).any(|b| b == b"avis"))
+}
+
+/// In-memory result of zapping a file's bytes: either the cleaned bytes, or a
+/// reason the file was skipped (e.g. a multi-frame animation that would be
+/// flattened by the re-encode fallback).
+enum ZapBytes {
+    Written(Vec<u8>),
+    Skipped(String),
+}
+
+/// Produce the cleaned bytes for an image: strip metadata segments byte-for-byte
+/// by default, or fully re-encode the pixels when `reencode` is set or the format
+/// has no dedicated parser. PNG output is additionally run through oxipng when
+/// `optimize` is set. This is the in-memory core shared by the directory
+/// (`process_image`) and archive (`append_to_archive`) output paths, so the
+/// multi-frame guard below applies to both.
+fn zap_bytes(src: &Path, lower: &str, optimize: bool, reencode: bool) -> Result<ZapBytes> {
+    // The re-encode fallback flattens animations to their first frame; refuse
+    // rather than quietly destroying the other frames.
+    if !reencode && is_multiframe(src, lower) {
+        return Ok(ZapBytes::Skipped(format!(
+            "multi-frame {} would be flattened by the re-encode fallback (only JPEG/PNG are lossless)",
+            lower.to_uppercase()
+        )));
+    }
+
+    let mut bytes = if reencode {
+        reencode_to_memory(src, lower)?
+    } else {
+        let data = fs::read(src)?;
+        match lower {
+            "jpg" | "jpeg" => strip_jpeg(&data)
+                .with_context(|| format!("Failed to strip {}", src.display()))?,
+            "png" => strip_png(&data)
+                .with_context(|| format!("Failed to strip {}", src.display()))?,
+            // Formats without a dedicated parser fall back to a full re-encode.
+            _ => reencode_to_memory(src, lower)?,
+        }
+    };
+
+    if optimize && lower == "png" {
         let opts = Options::from_preset(2);
-        let optimized = optimize_from_memory(&data, &opts)?;
-        fs::write(dest, &optimized)?;
+        bytes = optimize_from_memory(&bytes, &opts)?;
+    }
+
+    Ok(ZapBytes::Written(bytes))
+}
+
+/// Decode an image and re-encode it to an in-memory buffer in its own format.
+fn reencode_to_memory(src: &Path, lower: &str) -> Result<Vec<u8>> {
+    let img = ImageReader::open(src)?.decode()?;
+    let format = image::ImageFormat::from_extension(lower)
+        .with_context(|| format!("Unknown image format for '.{}'", lower))?;
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, format)
+        .with_context(|| format!("Failed to encode {}", lower.to_uppercase()))?;
+    Ok(buf.into_inner())
+}
+
+/// Compression sink backing the archive's tar stream. Kept as a concrete enum
+/// (rather than a boxed `Write`) so the encoder can be finalized on close.
+enum ArchiveSink {
+    Zstd(zstd::Encoder<'static, fs::File>),
+    Gzip(flate2::write::GzEncoder<fs::File>),
+    Xz(xz2::write::XzEncoder<fs::File>),
+}
+
+impl Write for ArchiveSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ArchiveSink::Zstd(w) => w.write(buf),
+            ArchiveSink::Gzip(w) => w.write(buf),
+            ArchiveSink::Xz(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ArchiveSink::Zstd(w) => w.flush(),
+            ArchiveSink::Gzip(w) => w.flush(),
+            ArchiveSink::Xz(w) => w.flush(),
+        }
+    }
+}
+
+impl ArchiveSink {
+    /// Flush any buffered data and write the codec trailer.
+    fn finish(self) -> Result<()> {
+        match self {
+            ArchiveSink::Zstd(w) => {
+                w.finish()?;
+            }
+            ArchiveSink::Gzip(w) => {
+                w.finish()?;
+            }
+            ArchiveSink::Xz(w) => {
+                w.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Create a tar builder whose output is compressed with the chosen codec. The
+/// optional `window` tunes the zstd window-log or the gzip/xz compression level.
+fn archive_builder(
+    path: &Path,
+    codec: Compression,
+    window: Option<u32>,
+) -> Result<tar::Builder<ArchiveSink>> {
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create archive {}", path.display()))?;
+    let sink = match codec {
+        Compression::Zstd => {
+            let mut enc = zstd::Encoder::new(file, 0)?;
+            if let Some(w) = window_log(window)? {
+                enc.window_log(w)?;
+            }
+            ArchiveSink::Zstd(enc)
+        }
+        Compression::Gzip => {
+            ArchiveSink::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::new(level(window, 6)?)))
+        }
+        Compression::Xz => ArchiveSink::Xz(xz2::write::XzEncoder::new(file, level(window, 6)?)),
+    };
+    Ok(tar::Builder::new(sink))
+}
+
+/// Interpret `--window` as a zstd window-log, which (unlike a gzip/xz level)
+/// must lie in 10..=27 (`ZSTD_WINDOWLOG_MIN` up to `ZSTD_WINDOWLOG_LIMIT_DEFAULT`,
+/// the largest window zstd's decoder, as used by `extract_archive`, accepts by
+/// default). Reject out-of-range values
+/// here rather than letting zstd fail opaquely mid-archive on a value the user
+/// likely meant as a 0-9 gzip/xz level.
+fn window_log(window: Option<u32>) -> Result<Option<u32>> {
+    match window {
+        Some(w) if !(10..=27).contains(&w) => anyhow::bail!(
+            "--window {} is out of range for zstd (expected a 10-27 window-log; \
+             did you mean a 0-9 level for --compression gzip/xz?)",
+            w
+        ),
+        Some(w) => Ok(Some(w)),
+        None => Ok(None),
+    }
+}
+
+/// Interpret `--window` as a gzip/xz compression level, which (unlike a zstd
+/// window-log) must lie in 0..=9. Reject out-of-range values rather than
+/// letting a zstd-sized window-log silently become an invalid level.
+fn level(window: Option<u32>, default: u32) -> Result<u32> {
+    match window {
+        Some(w) if w > 9 => anyhow::bail!(
+            "--window {} is out of range for gzip/xz (expected a 0-9 compression level)",
+            w
+        ),
+        Some(w) => Ok(w),
+        None => Ok(default),
+    }
+}
+
+/// Zap an image in memory and append it to the archive under its path relative
+/// to the input root. The tar append is serialized; the decode/strip is not.
+/// Shares `zap_bytes` with `process_image`, so a multi-frame animation is
+/// skipped here too rather than silently flattened into the archive.
+fn append_to_archive(
+    writer: &Mutex<tar::Builder<ArchiveSink>>,
+    src: &Path,
+    dest: &Path,
+    input_root: &Path,
+    ext: &str,
+    optimize: bool,
+    reencode: bool,
+) -> Result<Outcome> {
+    let lower = ext.to_lowercase();
+    let bytes = match zap_bytes(src, &lower, optimize, reencode)? {
+        ZapBytes::Skipped(reason) => return Ok(Outcome::Skipped(reason)),
+        ZapBytes::Written(bytes) => bytes,
+    };
+    let rel = src.strip_prefix(input_root).unwrap_or(src);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0); // don't leak original timestamps into the bundle
+
+    let mut builder = writer.lock().unwrap();
+    builder
+        .append_data(&mut header, rel, bytes.as_slice())
+        .with_context(|| format!("Failed to append {} to archive", src.display()))?;
+    Ok(Outcome::Written(dest.to_path_buf()))
+}
+
+/// Read a metazap archive back out into `dest` using `codec`, returning the
+/// number of entries.
+fn extract_archive(archive: &Path, dest: &Path, codec: Compression) -> Result<usize> {
+    use std::io::Read;
+
+    let file = fs::File::open(archive)
+        .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+    let reader: Box<dyn Read> = match codec {
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new(file)),
+    };
+
+    fs::create_dir_all(dest)
+        .with_context(|| format!("Failed to create output directory {}", dest.display()))?;
+
+    let mut ar = tar::Archive::new(reader);
+    let mut count = 0;
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        entry.unpack_in(dest)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Whether an extended attribute name belongs to a user-facing metadata
+/// namespace worth scrubbing: every `com.apple.*` attribute (macOS, including
+/// Finder tags and `com.apple.metadata:*`), and on Linux only `user.xdg.*` (the
+/// desktop metadata namespace, e.g. tags) rather than all of `user.*`, so unrelated
+/// `user.*` attributes an application relies on survive. Deliberately
+/// excludes kernel-managed namespaces like `security.*` / `system.*` (e.g.
+/// SELinux labels, POSIX ACLs), which a non-root process cannot remove and
+/// which carry no image metadata.
+fn is_privacy_xattr(name: &std::ffi::OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with("user.xdg.") || name.starts_with("com.apple.")
+}
+
+/// Remove the privacy-relevant extended attributes from `path` (Finder tags,
+/// `com.apple.metadata:*`, `user.xdg.*`), leaving kernel-managed namespaces
+/// alone. Returns the number cleared.
+fn clear_xattrs(path: &Path) -> Result<usize> {
+    let mut cleared = 0;
+    for name in xattr::list(path).with_context(|| format!("Failed to list xattrs on {}", path.display()))? {
+        if !is_privacy_xattr(&name) {
+            continue;
+        }
+        xattr::remove(path, &name)
+            .with_context(|| format!("Failed to remove xattr {:?} from {}", name, path.display()))?;
+        cleared += 1;
+    }
+    Ok(cleared)
+}
+
+/// Where a found sidecar ends up, mirroring wherever the image itself is
+/// written: deleted from the source for a true in-place run, copied next to
+/// the cleaned output for `--output`, or appended into the tar stream for
+/// `--archive`. Only the in-place case ever removes anything from the input.
+enum SidecarDest<'a> {
+    InPlace,
+    Directory(&'a Path),
+    Archive(&'a Mutex<tar::Builder<ArchiveSink>>),
+}
+
+/// Handle sidecar metadata files sharing the image's stem (`photo.xmp`,
+/// `photo.aae`, `photo.json`) per `dest`, honouring `dry_run` and (for
+/// `SidecarDest::InPlace`) `backup`. Returns the number of sidecars handled
+/// (or that would be handled under `dry_run`).
+fn handle_sidecars(
+    src: &Path,
+    input_root: &Path,
+    dest: SidecarDest,
+    dry_run: bool,
+    backup: bool,
+) -> Result<usize> {
+    let stem = match src.file_stem().and_then(|s| s.to_str()) {
+        Some(s) => s,
+        None => return Ok(0),
+    };
+    let dir = src.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut handled = 0;
+    for ext in SIDECAR_EXTS {
+        let sidecar = dir.join(format!("{}.{}", stem, ext));
+        if !sidecar.is_file() {
+            continue;
+        }
+
+        match &dest {
+            SidecarDest::InPlace => {
+                if dry_run {
+                    println!("  └─ Would remove sidecar: {}", sidecar.display());
+                    handled += 1;
+                    continue;
+                }
+
+                if backup {
+                    let mut backup_path = sidecar.clone();
+                    let bak_ext = format!("bak.{}", ext);
+                    backup_path.set_extension(bak_ext);
+                    fs::copy(&sidecar, &backup_path)
+                        .with_context(|| format!("Failed to back up sidecar {}", sidecar.display()))?;
+                }
+
+                fs::remove_file(&sidecar)
+                    .with_context(|| format!("Failed to remove sidecar {}", sidecar.display()))?;
+                println!("  └─ Removed sidecar: {}", sidecar.display());
+                handled += 1;
+            }
+            SidecarDest::Directory(out_dir) => {
+                let out_path = out_dir.join(sidecar.file_name().unwrap());
+                if dry_run {
+                    println!("  └─ Would copy sidecar to: {}", out_path.display());
+                    handled += 1;
+                    continue;
+                }
+
+                fs::copy(&sidecar, &out_path)
+                    .with_context(|| format!("Failed to copy sidecar to {}", out_path.display()))?;
+                println!("  └─ Copied sidecar to: {}", out_path.display());
+                handled += 1;
+            }
+            SidecarDest::Archive(writer) => {
+                let rel = sidecar.strip_prefix(input_root).unwrap_or(&sidecar);
+                if dry_run {
+                    println!("  └─ Would add sidecar to archive: {}", rel.display());
+                    handled += 1;
+                    continue;
+                }
+
+                let data = fs::read(&sidecar)
+                    .with_context(|| format!("Failed to read sidecar {}", sidecar.display()))?;
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(0);
+                writer
+                    .lock()
+                    .unwrap()
+                    .append_data(&mut header, rel, data.as_slice())
+                    .with_context(|| format!("Failed to add sidecar {} to archive", sidecar.display()))?;
+                println!("  └─ Added sidecar to archive: {}", rel.display());
+                handled += 1;
+            }
+        }
     }
+    Ok(handled)
+}
+
+/// Remux a video container, copying every stream packet without re-encoding
+/// while dropping container- and stream-level metadata, chapters, and
+/// attachments — the equivalent of `ffmpeg -map_metadata -1 -c copy`.
+#[cfg(feature = "video")]
+fn strip_video(src: &Path, dest: &Path) -> Result<()> {
+    use ffmpeg_next as ffmpeg;
+    use ffmpeg::format::context;
 
+    ffmpeg::init().context("failed to initialize ffmpeg")?;
+
+    // In-place runs have dest == src; muxing straight to that path would
+    // truncate the file we are still reading packets from. Always remux to a
+    // sibling temp file (keeping the extension so ffmpeg can pick the muxer)
+    // and rename it over the destination once the trailer is written.
+    let tmp = temp_sibling(dest);
+
+    let ictx = context::input(&src)
+        .with_context(|| format!("failed to open input {}", src.display()))?;
+    let mut octx = context::output(&tmp)
+        .with_context(|| format!("failed to open output {}", tmp.display()))?;
+
+    // Map every input stream to an output stream with the same parameters, and
+    // build the input->output index mapping for packet rewriting. Attachment
+    // streams (MKV fonts, `attached_pic` cover art) are dropped entirely rather
+    // than re-muxed, same as chapters below.
+    let mut mapping = vec![-1i32; ictx.nb_streams() as usize];
+    let mut out_index = 0usize;
+    for ist in ictx.streams() {
+        let is_attachment = ist.parameters().medium() == ffmpeg::media::Type::Attachment
+            || ist.disposition().contains(ffmpeg::format::stream::Disposition::ATTACHED_PIC);
+        if is_attachment {
+            continue;
+        }
+
+        let mut ost = octx.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))?;
+        ost.set_parameters(ist.parameters());
+        // Drop per-stream metadata.
+        ost.set_metadata(ffmpeg::Dictionary::new());
+        unsafe {
+            (*ost.as_mut_ptr()).codecpar.as_mut().unwrap().codec_tag = 0;
+        }
+        mapping[ist.index()] = out_index as i32;
+        out_index += 1;
+    }
+
+    // Drop container-level metadata and chapters (simply not copied).
+    octx.set_metadata(ffmpeg::Dictionary::new());
+    octx.write_header()?;
+
+    let mut ictx = ictx;
+    for (stream, mut packet) in ictx.packets() {
+        let out_idx = mapping[stream.index()];
+        if out_idx < 0 {
+            continue;
+        }
+        let ost = octx.stream(out_idx as usize).unwrap();
+        packet.rescale_ts(stream.time_base(), ost.time_base());
+        packet.set_position(-1);
+        packet.set_stream(out_idx as usize);
+        packet.write_interleaved(&mut octx)?;
+    }
+
+    octx.write_trailer()?;
+    drop(octx);
+
+    fs::rename(&tmp, dest)
+        .with_context(|| format!("failed to move remuxed video into {}", dest.display()))?;
     Ok(())
 }
+
+/// Build a temp path alongside `dest` that preserves its extension, so the
+/// remuxed output lands on the same filesystem (cheap rename) and ffmpeg can
+/// still infer the muxer from the suffix.
+#[cfg(feature = "video")]
+fn temp_sibling(dest: &Path) -> PathBuf {
+    let file_name = dest.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let dir = dest.parent().unwrap_or_else(|| Path::new("."));
+    dir.join(format!(".metazap-tmp-{}", file_name))
+}
+
+/// Handle a RAW camera file. By default the embedded EXIF/maker-note metadata is
+/// stripped from the TIFF container while the raw sensor data is left untouched;
+/// with `develop`, the file is demosaicked and exported as a metadata-free PNG.
+/// Fujifilm RAF is not a TIFF container, so it can only go through the
+/// `develop` path — skip it rather than letting the strip path bail.
+#[cfg(feature = "raw")]
+fn process_raw(src: &Path, dest: &Path, ext: &str, develop: bool) -> Result<Outcome> {
+    if ext == "raf" && !develop {
+        return Ok(Outcome::Skipped(format!(
+            "RAF {} is not a TIFF-based container and can't be stripped in place (try --develop)",
+            src.display()
+        )));
+    }
+
+    if develop {
+        let decoded = imagepipe::simple_decode_8bit(src, 0, 0)
+            .map_err(|e| anyhow::anyhow!("Failed to develop RAW {}: {}", src.display(), e))?;
+        let (w, h) = (decoded.width, decoded.height);
+        // imagepipe returns interleaved 8-bit RGB; confirm the stride before
+        // handing the buffer to `image`, otherwise `from_raw` silently yields
+        // None and the real fault is masked as a buffer-size error.
+        let expected = w.checked_mul(h).and_then(|n| n.checked_mul(3));
+        if expected != Some(decoded.data.len()) {
+            anyhow::bail!(
+                "Developed RAW {} has {} bytes, expected {}x{}x3 = {:?} (unexpected channel layout)",
+                src.display(),
+                decoded.data.len(),
+                w,
+                h,
+                expected
+            );
+        }
+        let buf = image::RgbImage::from_raw(w as u32, h as u32, decoded.data)
+            .context("Developed RAW buffer size did not match dimensions")?;
+        // Export as PNG regardless of the source extension; no metadata is written.
+        let out = dest.with_extension("png");
+        buf.save(&out)
+            .with_context(|| format!("Failed to save developed {}", out.display()))?;
+        return Ok(Outcome::Written(out));
+    }
+
+    let data = fs::read(src)?;
+    let stripped = strip_tiff_metadata(&data).with_context(|| {
+        format!("Failed to strip RAW metadata from {} (try --develop)", src.display())
+    })?;
+    fs::write(dest, &stripped)
+        .with_context(|| format!("Failed to write {}", dest.display()))?;
+    Ok(Outcome::Written(dest.to_path_buf()))
+}
+
+/// Byte size of a TIFF field type (BYTE/ASCII/SHORT/LONG/RATIONAL/...); unknown
+/// types are treated as single bytes so the length calculation never overshoots.
+#[cfg(feature = "raw")]
+fn tiff_type_size(t: u16) -> usize {
+    match t {
+        1 | 2 | 6 | 7 => 1, // BYTE, ASCII, SBYTE, UNDEFINED
+        3 | 8 => 2,         // SHORT, SSHORT
+        4 | 9 | 11 => 4,    // LONG, SLONG, FLOAT
+        5 | 10 | 12 => 8,   // RATIONAL, SRATIONAL, DOUBLE
+        _ => 1,
+    }
+}
+
+/// Overwrite `out[off..off+len]` with zeros, clamped to the buffer bounds.
+#[cfg(feature = "raw")]
+fn zero_region(out: &mut [u8], off: usize, len: usize) {
+    let end = off.saturating_add(len).min(out.len());
+    if off < end {
+        for b in &mut out[off..end] {
+            *b = 0;
+        }
+    }
+}
+
+/// Recursively zero a sub-IFD: every entry's out-of-line value block, any
+/// further nested sub-IFDs (Exif → Interoperability, GPS, MakerNote), and
+/// finally the IFD table itself. Reads structure from the untouched `data` and
+/// writes zeros into `out`; `depth` guards against cyclic offsets.
+#[cfg(feature = "raw")]
+fn zero_ifd(data: &[u8], out: &mut [u8], ifd: usize, little: bool, depth: u8) {
+    let rd16 = |b: &[u8]| if little { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) };
+    let rd32 = |b: &[u8]| {
+        if little {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        }
+    };
+
+    if depth > 8 || ifd + 2 > data.len() {
+        return;
+    }
+    let count = rd16(&data[ifd..ifd + 2]) as usize;
+    let entries_start = ifd + 2;
+    if entries_start + count * 12 + 4 > data.len() {
+        return;
+    }
+
+    for i in 0..count {
+        let entry = &data[entries_start + i * 12..entries_start + (i + 1) * 12];
+        let tag = rd16(&entry[0..2]);
+        let typ = rd16(&entry[2..4]);
+        let cnt = rd32(&entry[4..8]) as usize;
+        let byte_len = tiff_type_size(typ).saturating_mul(cnt);
+        if matches!(tag, 0x8769 | 0x8825 | 0xA005) {
+            // Nested sub-IFD pointer: recurse before the value offset is lost.
+            zero_ifd(data, out, rd32(&entry[8..12]) as usize, little, depth + 1);
+        } else if byte_len > 4 {
+            // Out-of-line value; the last 4 entry bytes hold its offset.
+            zero_region(out, rd32(&entry[8..12]) as usize, byte_len);
+        }
+    }
+
+    // Finally zero the IFD table (count + entries + next-IFD offset) itself.
+    zero_region(out, ifd, 2 + count * 12 + 4);
+}
+
+/// Strip EXIF/GPS/maker-note metadata from a TIFF-based container (DNG and the
+/// TIFF-derived CR2/NEF/ARW RAW formats). The relevant IFD0 entries are dropped
+/// *and* the data they reference — out-of-line value blocks and the entire
+/// EXIF/GPS/MakerNote sub-IFD trees — is overwritten with zeros, so nothing
+/// recoverable (GPS coordinates, device serials, timestamps) survives the scan.
+/// Every other byte offset is left untouched, keeping the remaining IFDs and the
+/// raw image strips valid.
+#[cfg(feature = "raw")]
+fn strip_tiff_metadata(data: &[u8]) -> Result<Vec<u8>> {
+    // Metadata-bearing IFD0 tags: textual tags, timestamps, and the EXIF/GPS/
+    // MakerNote sub-IFD pointers (whose targets become orphaned once dropped).
+    const DROP_TAGS: &[u16] = &[
+        0x010E, // ImageDescription
+        0x0132, // DateTime
+        0x013B, // Artist
+        0x8298, // Copyright
+        0x8769, // Exif IFD pointer
+        0x8825, // GPS IFD pointer
+        0x927C, // MakerNote
+        0xC4A5, // PrintIM
+    ];
+
+    if data.len() < 8 {
+        anyhow::bail!("file too short to be TIFF");
+    }
+    let little = match &data[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => anyhow::bail!("not a TIFF-based RAW (bad byte order mark)"),
+    };
+    let rd16 = |b: &[u8]| if little { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) };
+    let rd32 = |b: &[u8]| {
+        if little {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        }
+    };
+
+    if rd16(&data[2..4]) != 42 {
+        anyhow::bail!("not a TIFF-based RAW (bad magic)");
+    }
+    let ifd0 = rd32(&data[4..8]) as usize;
+    if ifd0 + 2 > data.len() {
+        anyhow::bail!("IFD0 offset overruns file");
+    }
+    let count = rd16(&data[ifd0..ifd0 + 2]) as usize;
+    let entries_start = ifd0 + 2;
+    let next_ifd_pos = entries_start + count * 12;
+    if next_ifd_pos + 4 > data.len() {
+        anyhow::bail!("IFD0 overruns file");
+    }
+
+    let mut out = data.to_vec();
+    let mut kept: Vec<&[u8]> = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = &data[entries_start + i * 12..entries_start + (i + 1) * 12];
+        let tag = rd16(&entry[0..2]);
+        if DROP_TAGS.contains(&tag) {
+            // Erase what the dropped entry points at, not just the entry.
+            let typ = rd16(&entry[2..4]);
+            let cnt = rd32(&entry[4..8]) as usize;
+            let byte_len = tiff_type_size(typ).saturating_mul(cnt);
+            if matches!(tag, 0x8769 | 0x8825) {
+                zero_ifd(data, &mut out, rd32(&entry[8..12]) as usize, little, 0);
+            } else if byte_len > 4 {
+                zero_region(&mut out, rd32(&entry[8..12]) as usize, byte_len);
+            }
+        } else {
+            kept.push(entry);
+        }
+    }
+
+    if kept.len() == count {
+        // Nothing to strip; return the bytes verbatim.
+        return Ok(out);
+    }
+
+    // Rewrite the (now shorter) IFD0 in place: new count, compacted entries, then
+    // the original next-IFD offset immediately after. Trailing freed bytes of the
+    // old IFD region are zeroed; all external offsets elsewhere are unchanged.
+    let new_count = kept.len() as u16;
+    let count_bytes = if little { new_count.to_le_bytes() } else { new_count.to_be_bytes() };
+    out[ifd0..ifd0 + 2].copy_from_slice(&count_bytes);
+    let mut pos = entries_start;
+    for entry in &kept {
+        out[pos..pos + 12].copy_from_slice(entry);
+        pos += 12;
+    }
+    out[pos..pos + 4].copy_from_slice(&data[next_ifd_pos..next_ifd_pos + 4]);
+    pos += 4;
+    for b in out[pos..next_ifd_pos + 4].iter_mut() {
+        *b = 0;
+    }
+
+    Ok(out)
+}
+
+/// Rewrite a JPEG's marker stream, dropping metadata-bearing segments (APP1
+/// EXIF/XMP, APP13 IPTC/Photoshop, and COM comments) while copying every other
+/// segment and the entropy-coded scan data verbatim.
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 2 || data[0] != 0xFF || data[1] != 0xD8 {
+        anyhow::bail!("not a JPEG (missing SOI marker)");
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[0..2]); // SOI
+    let mut i = 2;
+
+    while i + 1 < data.len() {
+        if data[i] != 0xFF {
+            anyhow::bail!("malformed JPEG: expected marker at offset {}", i);
+        }
+        let marker = data[i + 1];
+
+        // Standalone markers without a length field (RSTn, TEM, and padding 0xFF).
+        if marker == 0xD9 {
+            // EOI — copy and finish.
+            out.extend_from_slice(&data[i..i + 2]);
+            i += 2;
+            break;
+        }
+        if marker == 0xFF {
+            // Fill byte: a legal 0xFF padding byte preceding the next marker's
+            // own 0xFF prefix. Copy the single byte and advance by one so the
+            // second 0xFF is reparsed as that marker.
+            out.push(0xFF);
+            i += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            out.extend_from_slice(&data[i..i + 2]);
+            i += 2;
+            continue;
+        }
+
+        if i + 3 >= data.len() {
+            anyhow::bail!("truncated JPEG segment at offset {}", i);
+        }
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        let seg_end = i + 2 + len;
+        if seg_end > data.len() {
+            anyhow::bail!("JPEG segment length overruns file at offset {}", i);
+        }
+
+        let drop = matches!(marker, 0xE1 | 0xED | 0xFE); // APP1, APP13, COM
+        if !drop {
+            out.extend_from_slice(&data[i..seg_end]);
+        }
+        i = seg_end;
+
+        // Scan header (SOS) is followed by entropy-coded data up to EOI; copy the rest verbatim.
+        if marker == 0xDA {
+            out.extend_from_slice(&data[i..]);
+            return Ok(out);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Rewrite a PNG's chunk stream, dropping ancillary metadata chunks (tEXt, zTXt,
+/// iTXt, eXIf, tIME) while copying all other chunks — including their original
+/// CRCs — byte-for-byte.
+fn strip_png(data: &[u8]) -> Result<Vec<u8>> {
+    const SIG: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+    if data.len() < 8 || data[0..8] != SIG {
+        anyhow::bail!("not a PNG (bad signature)");
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&SIG);
+    let mut i = 8;
+
+    while i + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        let typ = &data[i + 4..i + 8];
+        let chunk_end = i + 12 + len; // length + type + data + CRC
+        if chunk_end > data.len() {
+            anyhow::bail!("PNG chunk length overruns file at offset {}", i);
+        }
+
+        let drop = matches!(typ, b"tEXt" | b"zTXt" | b"iTXt" | b"eXIf" | b"tIME");
+        if !drop {
+            out.extend_from_slice(&data[i..chunk_end]);
+        }
+        i = chunk_end;
+
+        if typ == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a minimal but valid JPEG: SOI, an APP1 (EXIF) segment, a SOS
+    /// header, one byte of entropy-coded scan data, then EOI.
+    fn sample_jpeg() -> Vec<u8> {
+        let mut d = vec![0xFF, 0xD8]; // SOI
+        d.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x08]); // APP1, length 8 (incl. the 2 length bytes)
+        d.extend_from_slice(b"Exif\0\0"); // 6 bytes of payload
+        d.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]); // SOS, length 2 (empty header)
+        d.push(0x12); // one byte of scan data
+        d.extend_from_slice(&[0xFF, 0xD9]); // EOI
+        d
+    }
+
+    #[test]
+    fn strip_jpeg_drops_app1_keeps_scan() {
+        let stripped = strip_jpeg(&sample_jpeg()).unwrap();
+        // APP1 marker and its "Exif" payload must be gone.
+        assert!(!stripped.windows(4).any(|w| w == b"Exif"));
+        assert!(!stripped.windows(2).any(|w| w == [0xFF, 0xE1]));
+        // Scan data and the SOI/SOS/EOI structure survive.
+        assert_eq!(&stripped[0..2], &[0xFF, 0xD8]);
+        assert!(stripped.windows(2).any(|w| w == [0xFF, 0xDA]));
+        assert!(stripped.contains(&0x12));
+        assert_eq!(&stripped[stripped.len() - 2..], &[0xFF, 0xD9]);
+    }
+
+    #[test]
+    fn strip_jpeg_tolerates_fill_byte_before_marker() {
+        // A legal 0xFF fill byte sits between SOI and the SOS marker.
+        let d = vec![0xFF, 0xD8, 0xFF, 0xFF, 0xDA, 0x00, 0x02, 0x12, 0xFF, 0xD9];
+        let stripped = strip_jpeg(&d).expect("fill byte must not abort parsing");
+        assert!(stripped.windows(2).any(|w| w == [0xFF, 0xDA]));
+        assert!(stripped.contains(&0x12));
+    }
+
+    #[test]
+    fn strip_jpeg_rejects_non_jpeg() {
+        assert!(strip_jpeg(&[0x00, 0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn strip_jpeg_rejects_truncated_segment() {
+        // SOI then a marker claiming a length that overruns the buffer.
+        assert!(strip_jpeg(&[0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF]).is_err());
+    }
+
+    /// Build a minimal PNG: signature, a stub IHDR, a tEXt chunk, and IEND.
+    /// CRCs are arbitrary — the stripper copies kept chunks verbatim and never
+    /// validates them.
+    fn sample_png() -> Vec<u8> {
+        let mut d = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        let mut chunk = |typ: &[u8; 4], data: &[u8], out: &mut Vec<u8>| {
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(typ);
+            out.extend_from_slice(data);
+            out.extend_from_slice(&[0, 0, 0, 0]); // placeholder CRC
+        };
+        chunk(b"IHDR", &[0; 13], &mut d);
+        chunk(b"tEXt", b"Comment\0secret", &mut d);
+        chunk(b"IEND", &[], &mut d);
+        d
+    }
+
+    #[test]
+    fn strip_png_drops_text_keeps_ihdr_iend() {
+        let stripped = strip_png(&sample_png()).unwrap();
+        assert!(!stripped.windows(4).any(|w| w == b"tEXt"));
+        assert!(!stripped.windows(6).any(|w| w == b"secret"));
+        assert!(stripped.windows(4).any(|w| w == b"IHDR"));
+        assert!(stripped.windows(4).any(|w| w == b"IEND"));
+    }
+
+    #[test]
+    fn strip_png_rejects_bad_signature() {
+        assert!(strip_png(b"not a png at all!!").is_err());
+    }
+
+    /// Little-endian TIFF with IFD0 holding a DateTime (out-of-line ASCII value)
+    /// and an Exif sub-IFD pointer; the sub-IFD carries one tag whose RATIONAL
+    /// value also lives out of line. Both the value blocks and the sub-IFD table
+    /// must be erased, not merely unlinked.
+    #[cfg(feature = "raw")]
+    fn sample_tiff() -> Vec<u8> {
+        let mut d = Vec::new();
+        d.extend_from_slice(b"II");
+        d.extend_from_slice(&42u16.to_le_bytes());
+        d.extend_from_slice(&8u32.to_le_bytes()); // IFD0 at offset 8
+        // IFD0
+        d.extend_from_slice(&2u16.to_le_bytes());
+        // DateTime (0x0132), ASCII, 8 chars, value at offset 38
+        d.extend_from_slice(&0x0132u16.to_le_bytes());
+        d.extend_from_slice(&2u16.to_le_bytes());
+        d.extend_from_slice(&8u32.to_le_bytes());
+        d.extend_from_slice(&38u32.to_le_bytes());
+        // Exif IFD pointer (0x8769), LONG, sub-IFD at offset 46
+        d.extend_from_slice(&0x8769u16.to_le_bytes());
+        d.extend_from_slice(&4u16.to_le_bytes());
+        d.extend_from_slice(&1u32.to_le_bytes());
+        d.extend_from_slice(&46u32.to_le_bytes());
+        d.extend_from_slice(&0u32.to_le_bytes()); // no next IFD
+        assert_eq!(d.len(), 38);
+        d.extend_from_slice(b"SECRET!\0"); // DateTime value
+        assert_eq!(d.len(), 46);
+        // Exif sub-IFD: one RATIONAL tag whose value lives at offset 64
+        d.extend_from_slice(&1u16.to_le_bytes());
+        d.extend_from_slice(&0x0002u16.to_le_bytes());
+        d.extend_from_slice(&5u16.to_le_bytes());
+        d.extend_from_slice(&3u32.to_le_bytes());
+        d.extend_from_slice(&64u32.to_le_bytes());
+        d.extend_from_slice(&0u32.to_le_bytes()); // no next IFD
+        assert_eq!(d.len(), 64);
+        d.extend_from_slice(b"GPSDATA_0123456789abcde!"); // 24 bytes
+        d
+    }
+
+    #[cfg(feature = "raw")]
+    #[test]
+    fn strip_tiff_erases_referenced_regions() {
+        let input = sample_tiff();
+        let out = strip_tiff_metadata(&input).unwrap();
+        // Offsets are preserved, so the file keeps its length and header.
+        assert_eq!(out.len(), input.len());
+        assert_eq!(&out[0..4], &input[0..4]);
+        // The actual secrets — not just their IFD0 pointers — are gone.
+        assert!(!out.windows(6).any(|w| w == b"SECRET"));
+        assert!(!out.windows(7).any(|w| w == b"GPSDATA"));
+        // IFD0 has been compacted down to zero entries.
+        assert_eq!(u16::from_le_bytes([out[8], out[9]]), 0);
+    }
+
+    #[cfg(feature = "raw")]
+    #[test]
+    fn strip_tiff_rejects_non_tiff() {
+        assert!(strip_tiff_metadata(&[0, 1, 2, 3, 4, 5, 6, 7, 8]).is_err());
+    }
+
+    /// Little-endian TIFF with `ifd_count` single-entry IFDs chained via each
+    /// IFD's next-IFD offset.
+    fn sample_tiff_pages(ifd_count: usize) -> Vec<u8> {
+        let mut d = vec![0u8; 8];
+        d[0..2].copy_from_slice(b"II");
+        d[2..4].copy_from_slice(&42u16.to_le_bytes());
+        d[4..8].copy_from_slice(&8u32.to_le_bytes()); // IFD0 at offset 8
+        for i in 0..ifd_count {
+            d.extend_from_slice(&1u16.to_le_bytes()); // one entry
+            d.extend_from_slice(&0x0100u16.to_le_bytes()); // ImageWidth tag
+            d.extend_from_slice(&3u16.to_le_bytes()); // SHORT
+            d.extend_from_slice(&1u32.to_le_bytes()); // count
+            d.extend_from_slice(&0u32.to_le_bytes()); // inline value
+            let next_ifd_pos = d.len();
+            let next = if i + 1 < ifd_count { (next_ifd_pos + 4) as u32 } else { 0 };
+            d.extend_from_slice(&next.to_le_bytes());
+        }
+        d
+    }
+
+    #[test]
+    fn is_multipage_tiff_detects_chained_ifds() {
+        assert!(!is_multipage_tiff(&sample_tiff_pages(1)).unwrap());
+        assert!(is_multipage_tiff(&sample_tiff_pages(2)).unwrap());
+    }
+
+    #[test]
+    fn is_multipage_tiff_rejects_bad_byte_order_mark() {
+        assert!(is_multipage_tiff(&[0, 1, 2, 3, 4, 5, 6, 7]).is_err());
+    }
+
+    /// Minimal `ftyp` box: size, "ftyp", major brand, minor version, then
+    /// `compat_brands` as 4-byte tags.
+    fn sample_ftyp(major: &[u8; 4], compat_brands: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + compat_brands.len() * 4;
+        let mut d = (size as u32).to_be_bytes().to_vec();
+        d.extend_from_slice(b"ftyp");
+        d.extend_from_slice(major);
+        d.extend_from_slice(&[0, 0, 0, 0]); // minor version
+        for b in compat_brands {
+            d.extend_from_slice(*b);
+        }
+        d
+    }
+
+    #[test]
+    fn is_animated_avif_true_for_avis_major_brand() {
+        assert!(is_animated_avif(&sample_ftyp(b"avis", &[])).unwrap());
+    }
+
+    #[test]
+    fn is_animated_avif_true_for_avis_compatible_brand() {
+        assert!(is_animated_avif(&sample_ftyp(b"avif", &[b"mif1", b"avis"])).unwrap());
+    }
+
+    #[test]
+    fn is_animated_avif_false_for_still() {
+        assert!(!is_animated_avif(&sample_ftyp(b"avif", &[b"mif1", b"miaf"])).unwrap());
+    }
+
+    #[test]
+    fn is_animated_avif_rejects_missing_ftyp() {
+        assert!(is_animated_avif(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).is_err());
+    }
+}